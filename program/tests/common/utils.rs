@@ -1,9 +1,12 @@
-use agnostic_orderbook::state::{EVENT_QUEUE_HEADER_LEN, MARKET_STATE_LEN, REGISTER_SIZE};
+use agnostic_orderbook::state::{
+    AccountTag, EventQueue, EventRef, Side, EVENT_QUEUE_HEADER_LEN, MARKET_STATE_LEN, REGISTER_SIZE,
+};
 use dex_v4::CALLBACK_INFO_LEN;
-use solana_program::instruction::Instruction;
+use solana_program::instruction::{AccountMeta, Instruction};
 use solana_program::program_pack::Pack;
 use solana_program::pubkey::Pubkey;
 use solana_program::system_instruction::create_account;
+use solana_program::system_program;
 use solana_program_test::BanksClientError;
 use solana_program_test::{ProgramTest, ProgramTestContext};
 use solana_sdk::account::Account;
@@ -11,6 +14,7 @@ use solana_sdk::signature::Signer;
 use solana_sdk::{signature::Keypair, transaction::Transaction};
 use spl_associated_token_account::{create_associated_token_account, get_associated_token_address};
 use spl_token::state::Mint;
+use std::collections::BTreeSet;
 use std::str::FromStr;
 
 pub async fn sign_send_instructions(
@@ -74,18 +78,207 @@ pub fn mint_bootstrap(
     (address, mint_info)
 }
 
+/// Builds and sends a `SendTake` instruction: an atomic taker order that
+/// settles straight into the taker's base/quote SPL accounts (IOC — nothing
+/// is posted to the book).
+#[allow(clippy::too_many_arguments)]
+pub async fn send_take(
+    prg_test_ctx: &mut ProgramTestContext,
+    market: &Pubkey,
+    event_queue: &Pubkey,
+    bids: &Pubkey,
+    asks: &Pubkey,
+    base_vault: &Pubkey,
+    quote_vault: &Pubkey,
+    market_signer: &Pubkey,
+    user_base_account: &Pubkey,
+    user_quote_account: &Pubkey,
+    user_owner: &Keypair,
+    side: Side,
+    max_base_qty: u64,
+    max_quote_qty: u64,
+    min_base_qty: u64,
+) -> Result<(), BanksClientError> {
+    let accounts = dex_v4::instruction::send_take::Accounts {
+        spl_token_program: &spl_token::ID,
+        market,
+        orderbook: market,
+        event_queue,
+        bids,
+        asks,
+        base_vault,
+        quote_vault,
+        market_signer,
+        user_base_account,
+        user_quote_account,
+        user_owner: &user_owner.pubkey(),
+    };
+    let params = dex_v4::instruction::send_take::Params {
+        side: side as u8,
+        max_base_qty,
+        max_quote_qty,
+        min_base_qty,
+    };
+    let instruction = dex_v4::instruction::send_take(accounts, params);
+    sign_send_instructions(prg_test_ctx, vec![instruction], vec![user_owner]).await
+}
+
+/// Collects the signers for a gated order-flow instruction: always the order
+/// owner, plus the permissioned-market authority when one is configured.
+fn order_flow_signers<'a>(
+    user_owner: &'a Keypair,
+    market_authority: Option<&'a Keypair>,
+) -> Vec<&'a Keypair> {
+    let mut signers = vec![user_owner];
+    if let Some(authority) = market_authority {
+        signers.push(authority);
+    }
+    signers
+}
+
+/// Builds and sends a `new_order`. On a permissioned market pass the
+/// configured `market_authority`: it is threaded as a required co-signer, so
+/// omitting it makes the program reject the order.
+#[allow(clippy::too_many_arguments)]
+pub async fn new_order(
+    prg_test_ctx: &mut ProgramTestContext,
+    market: &Pubkey,
+    event_queue: &Pubkey,
+    bids: &Pubkey,
+    asks: &Pubkey,
+    base_vault: &Pubkey,
+    quote_vault: &Pubkey,
+    user_account: &Pubkey,
+    user_token_account: &Pubkey,
+    user_owner: &Keypair,
+    market_authority: Option<&Keypair>,
+    side: Side,
+    limit_price: u64,
+    max_base_qty: u64,
+    max_quote_qty: u64,
+) -> Result<(), BanksClientError> {
+    let authority_key = market_authority.map(|kp| kp.pubkey());
+    let accounts = dex_v4::instruction::new_order::Accounts {
+        spl_token_program: &spl_token::ID,
+        market,
+        orderbook: market,
+        event_queue,
+        bids,
+        asks,
+        base_vault,
+        quote_vault,
+        user: user_account,
+        user_token_account,
+        user_owner: &user_owner.pubkey(),
+        market_authority: authority_key.as_ref(),
+    };
+    let params = dex_v4::instruction::new_order::Params {
+        side: side as u8,
+        limit_price,
+        max_base_qty,
+        max_quote_qty,
+    };
+    let instruction = dex_v4::instruction::new_order(accounts, params);
+    sign_send_instructions(
+        prg_test_ctx,
+        vec![instruction],
+        order_flow_signers(user_owner, market_authority),
+    )
+    .await
+}
+
+/// Builds and sends a `cancel_order`, threading the permissioned-market
+/// authority as a required co-signer when one is configured.
+#[allow(clippy::too_many_arguments)]
+pub async fn cancel_order(
+    prg_test_ctx: &mut ProgramTestContext,
+    market: &Pubkey,
+    event_queue: &Pubkey,
+    bids: &Pubkey,
+    asks: &Pubkey,
+    user_account: &Pubkey,
+    user_owner: &Keypair,
+    market_authority: Option<&Keypair>,
+    order_id: u128,
+) -> Result<(), BanksClientError> {
+    let authority_key = market_authority.map(|kp| kp.pubkey());
+    let accounts = dex_v4::instruction::cancel_order::Accounts {
+        market,
+        orderbook: market,
+        event_queue,
+        bids,
+        asks,
+        user: user_account,
+        user_owner: &user_owner.pubkey(),
+        market_authority: authority_key.as_ref(),
+    };
+    let params = dex_v4::instruction::cancel_order::Params { order_id };
+    let instruction = dex_v4::instruction::cancel_order(accounts, params);
+    sign_send_instructions(
+        prg_test_ctx,
+        vec![instruction],
+        order_flow_signers(user_owner, market_authority),
+    )
+    .await
+}
+
+/// Builds and sends a `settle`, threading the permissioned-market authority
+/// as a required co-signer when one is configured.
+#[allow(clippy::too_many_arguments)]
+pub async fn settle(
+    prg_test_ctx: &mut ProgramTestContext,
+    market: &Pubkey,
+    base_vault: &Pubkey,
+    quote_vault: &Pubkey,
+    market_signer: &Pubkey,
+    user_account: &Pubkey,
+    user_base_account: &Pubkey,
+    user_quote_account: &Pubkey,
+    user_owner: &Keypair,
+    market_authority: Option<&Keypair>,
+) -> Result<(), BanksClientError> {
+    let authority_key = market_authority.map(|kp| kp.pubkey());
+    let accounts = dex_v4::instruction::settle::Accounts {
+        spl_token_program: &spl_token::ID,
+        market,
+        base_vault,
+        quote_vault,
+        market_signer,
+        user: user_account,
+        user_base_account,
+        user_quote_account,
+        user_owner: &user_owner.pubkey(),
+        market_authority: authority_key.as_ref(),
+    };
+    let instruction = dex_v4::instruction::settle(accounts, dex_v4::instruction::settle::Params {});
+    sign_send_instructions(
+        prg_test_ctx,
+        vec![instruction],
+        order_flow_signers(user_owner, market_authority),
+    )
+    .await
+}
+
 pub struct AOBAccounts {
     pub event_queue: Pubkey,
     pub market: Pubkey,
     pub asks: Pubkey,
     pub bids: Pubkey,
+    /// Permissioned-market authority carried for downstream callers (e.g.
+    /// `create_market_and_accounts`). `None` for a permissionless market.
+    pub market_authority: Option<Pubkey>,
 }
 
-/// Creates the accounts needed for the AAOB market testing and returns the
-/// address of the market.
+/// Creates the orderbook accounts for the AAOB market and returns them.
+///
+/// `market_authority` is not used here (this function issues no
+/// `create_market`); it is only carried through to the returned struct.
 pub async fn create_aob_market_and_accounts(
     prg_test_ctx: &mut ProgramTestContext,
     dex_program_id: Pubkey,
+    event_capacity: usize,
+    orderbook_capacity: usize,
+    market_authority: Option<Pubkey>,
 ) -> AOBAccounts {
     let rent = prg_test_ctx.banks_client.get_rent().await.unwrap();
 
@@ -111,7 +304,8 @@ pub async fn create_aob_market_and_accounts(
     let event_queue_account = Keypair::new();
     let evq_space = agnostic_orderbook::state::EVENT_QUEUE_HEADER_LEN
         + REGISTER_SIZE
-        + 10 * (agnostic_orderbook::state::Event::compute_slot_size(CALLBACK_INFO_LEN as usize));
+        + event_capacity
+            * (agnostic_orderbook::state::Event::compute_slot_size(CALLBACK_INFO_LEN as usize));
     let create_event_queue_account_instruction = create_account(
         &prg_test_ctx.payer.pubkey(),
         &event_queue_account.pubkey(),
@@ -132,8 +326,8 @@ pub async fn create_aob_market_and_accounts(
     let create_bids_account_instruction = create_account(
         &prg_test_ctx.payer.pubkey(),
         &bids_account.pubkey(),
-        rent.minimum_balance(1_000_000),
-        1_000_000,
+        rent.minimum_balance(orderbook_capacity),
+        orderbook_capacity as u64,
         &dex_program_id,
     );
     sign_send_instructions(
@@ -149,8 +343,8 @@ pub async fn create_aob_market_and_accounts(
     let create_asks_account_instruction = create_account(
         &prg_test_ctx.payer.pubkey(),
         &asks_account.pubkey(),
-        rent.minimum_balance(1_000_000),
-        1_000_000,
+        rent.minimum_balance(orderbook_capacity),
+        orderbook_capacity as u64,
         &dex_program_id,
     );
     sign_send_instructions(
@@ -166,5 +360,300 @@ pub async fn create_aob_market_and_accounts(
         market: market_account.pubkey(),
         asks: asks_account.pubkey(),
         bids: bids_account.pubkey(),
+        market_authority,
+    }
+}
+
+/// Derives the market vault-signer address and its canonical bump.
+pub fn find_market_signer(market: &Pubkey, dex_program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[&market.to_bytes()], dex_program_id)
+}
+
+/// Mints `amount` of `mint` into `dest`, signed by the mint authority.
+pub async fn mint_to(
+    prg_test_ctx: &mut ProgramTestContext,
+    mint: &Pubkey,
+    dest: &Pubkey,
+    authority: &Keypair,
+    amount: u64,
+) -> Result<(), BanksClientError> {
+    let instruction = spl_token::instruction::mint_to(
+        &spl_token::ID,
+        mint,
+        dest,
+        &authority.pubkey(),
+        &[],
+        amount,
+    )
+    .unwrap();
+    sign_send_instructions(prg_test_ctx, vec![instruction], vec![authority]).await
+}
+
+/// Initializes the `owner`'s open-orders account for `market` and returns its
+/// PDA.
+pub async fn initialize_user_account(
+    prg_test_ctx: &mut ProgramTestContext,
+    market: &Pubkey,
+    owner: &Keypair,
+) -> Pubkey {
+    let (user_account, _) = Pubkey::find_program_address(
+        &[&market.to_bytes(), &owner.pubkey().to_bytes()],
+        &dex_v4::ID,
+    );
+    let accounts = dex_v4::instruction::initialize_account::Accounts {
+        system_program: &system_program::ID,
+        user: &user_account,
+        user_owner: &owner.pubkey(),
+        fee_payer: &prg_test_ctx.payer.pubkey(),
+    };
+    let params = dex_v4::instruction::initialize_account::Params {
+        market: *market,
+        max_orders: 10,
+    };
+    let instruction = dex_v4::instruction::initialize_account(accounts, params);
+    sign_send_instructions(prg_test_ctx, vec![instruction], vec![owner])
+        .await
+        .unwrap();
+    user_account
+}
+
+/// Creates an SPL token account for `mint` owned by `owner`.
+pub async fn create_token_account(
+    prg_test_ctx: &mut ProgramTestContext,
+    mint: &Pubkey,
+    owner: &Pubkey,
+) -> Pubkey {
+    let rent = prg_test_ctx.banks_client.get_rent().await.unwrap();
+    let account = Keypair::new();
+    let create_account_instruction = create_account(
+        &prg_test_ctx.payer.pubkey(),
+        &account.pubkey(),
+        rent.minimum_balance(spl_token::state::Account::LEN),
+        spl_token::state::Account::LEN as u64,
+        &spl_token::ID,
+    );
+    let init_account_instruction = spl_token::instruction::initialize_account(
+        &spl_token::ID,
+        &account.pubkey(),
+        mint,
+        owner,
+    )
+    .unwrap();
+    sign_send_instructions(
+        prg_test_ctx,
+        vec![create_account_instruction, init_account_instruction],
+        vec![&account],
+    )
+    .await
+    .unwrap();
+    account.pubkey()
+}
+
+/// The full set of keys produced when listing a market.
+pub struct MarketAccounts {
+    pub market: Pubkey,
+    pub event_queue: Pubkey,
+    pub bids: Pubkey,
+    pub asks: Pubkey,
+    pub base_vault: Pubkey,
+    pub quote_vault: Pubkey,
+    pub base_mint: Pubkey,
+    pub quote_mint: Pubkey,
+    pub market_signer: Pubkey,
+    pub vault_signer_nonce: u8,
+    pub market_admin: Pubkey,
+    pub market_authority: Option<Pubkey>,
+}
+
+/// Bootstraps a full market from the base/quote mints: orderbook accounts,
+/// market-signer PDA, vaults, and the `create_market` instruction.
+#[allow(clippy::too_many_arguments)]
+pub async fn create_market_and_accounts(
+    prg_test_ctx: &mut ProgramTestContext,
+    dex_program_id: Pubkey,
+    base_mint: &MintInfo,
+    quote_mint: &MintInfo,
+    market_admin: &Pubkey,
+    market_authority: Option<Pubkey>,
+    event_capacity: usize,
+    orderbook_capacity: usize,
+    min_base_order_size: u64,
+) -> MarketAccounts {
+    let aob = create_aob_market_and_accounts(
+        prg_test_ctx,
+        dex_program_id,
+        event_capacity,
+        orderbook_capacity,
+        market_authority,
+    )
+    .await;
+
+    // Derive the market-signer PDA using the canonical bump; `create_market`
+    // recomputes and asserts this same bump, so a non-canonical nonce would
+    // be rejected on-chain.
+    let (market_signer, vault_signer_nonce) = find_market_signer(&aob.market, &dex_program_id);
+
+    // Vaults are plain token accounts owned by the market-signer PDA.
+    let base_vault = create_token_account(prg_test_ctx, &base_mint.0, &market_signer).await;
+    let quote_vault = create_token_account(prg_test_ctx, &quote_mint.0, &market_signer).await;
+
+    let accounts = dex_v4::instruction::create_market::Accounts {
+        market: &aob.market,
+        orderbook: &aob.market,
+        event_queue: &aob.event_queue,
+        bids: &aob.bids,
+        asks: &aob.asks,
+        base_vault: &base_vault,
+        quote_vault: &quote_vault,
+        market_admin,
+    };
+    let params = dex_v4::instruction::create_market::Params {
+        signer_nonce: vault_signer_nonce as u64,
+        min_base_order_size,
+        market_authority,
+    };
+    let instruction = dex_v4::instruction::create_market(accounts, params);
+    sign_send_instructions(prg_test_ctx, vec![instruction], vec![])
+        .await
+        .unwrap();
+
+    MarketAccounts {
+        market: aob.market,
+        event_queue: aob.event_queue,
+        bids: aob.bids,
+        asks: aob.asks,
+        base_vault,
+        quote_vault,
+        base_mint: base_mint.0,
+        quote_mint: quote_mint.0,
+        market_signer,
+        vault_signer_nonce,
+        market_admin: *market_admin,
+        market_authority,
+    }
+}
+
+/// Maximum events cranked in a single `consume_events` call.
+pub const MAX_EVENTS_PER_CRANK: usize = 10;
+
+/// Total number of attempts (initial try plus retries) made per batch before
+/// the crank gives up on a transient `BanksClientError`.
+const CRANK_MAX_ATTEMPTS: usize = 5;
+
+/// Cranks the event queue through `consume_events` in batches of at most
+/// [`MAX_EVENTS_PER_CRANK`], retrying transient failures. Drains up to
+/// `max_batches` batches and returns the number of events consumed.
+pub async fn consume_events_crank(
+    prg_test_ctx: &mut ProgramTestContext,
+    market: &Pubkey,
+    event_queue: &Pubkey,
+    reward_target: &Pubkey,
+    max_batches: usize,
+) -> Result<u64, BanksClientError> {
+    let mut consumed = 0;
+    for _ in 0..max_batches {
+        let (user_accounts, event_count) =
+            pending_user_accounts(prg_test_ctx, event_queue).await?;
+        if event_count == 0 {
+            break;
+        }
+
+        // Build the account-meta list deterministically from the sorted set
+        // of referenced open-orders accounts so the ordering is stable.
+        let user_metas: Vec<AccountMeta> = user_accounts
+            .iter()
+            .map(|k| AccountMeta::new(*k, false))
+            .collect();
+        let accounts = dex_v4::instruction::consume_events::Accounts {
+            market,
+            orderbook: market,
+            event_queue,
+            reward_target,
+        };
+        let params = dex_v4::instruction::consume_events::Params {
+            max_iterations: MAX_EVENTS_PER_CRANK as u64,
+        };
+        let mut instruction = dex_v4::instruction::consume_events(accounts, params);
+        instruction.accounts.extend(user_metas);
+
+        let mut attempt = 0;
+        loop {
+            match sign_send_instructions(prg_test_ctx, vec![instruction.clone()], vec![]).await {
+                Ok(()) => break,
+                Err(_) if attempt + 1 < CRANK_MAX_ATTEMPTS => attempt += 1,
+                Err(err) => return Err(err),
+            }
+        }
+        consumed += event_count as u64;
+    }
+    Ok(consumed)
+}
+
+/// Returns the (deduplicated, sorted) open-orders accounts referenced by the
+/// first [`MAX_EVENTS_PER_CRANK`] pending events and the number scanned.
+async fn pending_user_accounts(
+    prg_test_ctx: &mut ProgramTestContext,
+    event_queue: &Pubkey,
+) -> Result<(BTreeSet<Pubkey>, usize), BanksClientError> {
+    let mut data = match prg_test_ctx.banks_client.get_account(*event_queue).await? {
+        Some(account) => account.data,
+        None => return Ok((BTreeSet::new(), 0)),
+    };
+    let event_queue = EventQueue::from_buffer(&mut data, AccountTag::EventQueue);
+    let mut user_accounts = BTreeSet::new();
+    let mut event_count = 0;
+    for event in event_queue.iter().take(MAX_EVENTS_PER_CRANK) {
+        match event {
+            EventRef::Fill(fill) => {
+                user_accounts.insert(Pubkey::new(&fill.maker_callback_info[..32]));
+            }
+            EventRef::Out(out) => {
+                user_accounts.insert(Pubkey::new(&out.callback_info[..32]));
+            }
+        }
+        event_count += 1;
+    }
+    Ok((user_accounts, event_count))
+}
+
+/// Scans the book and returns the fully-crossed `(crossed_bids,
+/// crossed_asks)` order ids so a keeper can prune them.
+pub async fn find_crossed_orders(
+    prg_test_ctx: &mut ProgramTestContext,
+    bids: &Pubkey,
+    asks: &Pubkey,
+) -> Result<(Vec<u128>, Vec<u128>), BanksClientError> {
+    let mut bids_data = match prg_test_ctx.banks_client.get_account(*bids).await? {
+        Some(account) => account.data,
+        None => return Ok((Vec::new(), Vec::new())),
+    };
+    let mut asks_data = match prg_test_ctx.banks_client.get_account(*asks).await? {
+        Some(account) => account.data,
+        None => return Ok((Vec::new(), Vec::new())),
+    };
+    let bid_slab = agnostic_orderbook::state::Slab::from_buffer(&mut bids_data, AccountTag::Bids);
+    let ask_slab = agnostic_orderbook::state::Slab::from_buffer(&mut asks_data, AccountTag::Asks);
+
+    let best_ask = ask_slab.find_min().map(|h| ask_slab.get_node(h).unwrap().price());
+    let best_bid = bid_slab.find_max().map(|h| bid_slab.get_node(h).unwrap().price());
+
+    let mut crossed_bids = Vec::new();
+    if let Some(best_ask) = best_ask {
+        for handle in bid_slab.into_iter(true) {
+            let node = bid_slab.get_node(handle).unwrap();
+            if node.price() >= best_ask {
+                crossed_bids.push(node.key);
+            }
+        }
+    }
+    let mut crossed_asks = Vec::new();
+    if let Some(best_bid) = best_bid {
+        for handle in ask_slab.into_iter(true) {
+            let node = ask_slab.get_node(handle).unwrap();
+            if node.price() <= best_bid {
+                crossed_asks.push(node.key);
+            }
+        }
     }
+    Ok((crossed_bids, crossed_asks))
 }
@@ -0,0 +1,89 @@
+use agnostic_orderbook::state::Side;
+use dex_v4::CALLBACK_INFO_LEN;
+use solana_program_test::{processor, ProgramTest};
+use solana_sdk::signature::{Keypair, Signer};
+
+mod common;
+use common::utils::{
+    create_market_and_accounts, create_token_account, initialize_user_account, mint_to, new_order,
+};
+
+/// A permissioned market rejects order flow that does not carry the configured
+/// authority as a signer, and accepts it once the authority co-signs.
+#[tokio::test]
+async fn test_permissioned_market_gating() {
+    let mut program_test =
+        ProgramTest::new("dex_v4", dex_v4::ID, processor!(dex_v4::entrypoint::process));
+
+    let mint_authority = Keypair::new();
+    let base_mint = common::utils::mint_bootstrap(None, 6, &mut program_test, &mint_authority.pubkey());
+    let quote_mint =
+        common::utils::mint_bootstrap(None, 6, &mut program_test, &mint_authority.pubkey());
+
+    let mut ctx = program_test.start_with_context().await;
+
+    let market_admin = Keypair::new();
+    let market_authority = Keypair::new();
+    let market = create_market_and_accounts(
+        &mut ctx,
+        dex_v4::ID,
+        &base_mint,
+        &quote_mint,
+        &market_admin.pubkey(),
+        Some(market_authority.pubkey()),
+        10 * (CALLBACK_INFO_LEN as usize + 1),
+        1_000_000,
+        1,
+    )
+    .await;
+
+    // Fund a user and open their orders account.
+    let user = Keypair::new();
+    let user_quote = create_token_account(&mut ctx, &market.quote_mint, &user.pubkey()).await;
+    mint_to(&mut ctx, &market.quote_mint, &user_quote, &mint_authority, 1_000_000)
+        .await
+        .unwrap();
+    let user_account = initialize_user_account(&mut ctx, &market.market, &user).await;
+
+    // Without the authority co-signing, the order is rejected.
+    let rejected = new_order(
+        &mut ctx,
+        &market.market,
+        &market.event_queue,
+        &market.bids,
+        &market.asks,
+        &market.base_vault,
+        &market.quote_vault,
+        &user_account,
+        &user_quote,
+        &user,
+        None,
+        Side::Bid,
+        1000,
+        1000,
+        1_000_000,
+    )
+    .await;
+    assert!(rejected.is_err(), "order flow must be gated without the authority");
+
+    // With the authority co-signing, the order is accepted.
+    let accepted = new_order(
+        &mut ctx,
+        &market.market,
+        &market.event_queue,
+        &market.bids,
+        &market.asks,
+        &market.base_vault,
+        &market.quote_vault,
+        &user_account,
+        &user_quote,
+        &user,
+        Some(&market_authority),
+        Side::Bid,
+        1000,
+        1000,
+        1_000_000,
+    )
+    .await;
+    assert!(accepted.is_ok(), "authority co-signed order must be accepted");
+}
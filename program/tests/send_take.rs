@@ -0,0 +1,244 @@
+use agnostic_orderbook::state::Side;
+use dex_v4::CALLBACK_INFO_LEN;
+use solana_program::program_pack::Pack;
+use solana_program::pubkey::Pubkey;
+use solana_program_test::{processor, ProgramTest, ProgramTestContext};
+use solana_sdk::signature::{Keypair, Signer};
+
+mod common;
+use common::utils::{
+    consume_events_crank, create_market_and_accounts, create_token_account,
+    initialize_user_account, mint_to, new_order, send_take, MarketAccounts,
+};
+
+const EVENT_CAPACITY: usize = 10;
+
+async fn token_balance(ctx: &mut ProgramTestContext, account: &Pubkey) -> u64 {
+    let raw = ctx.banks_client.get_account(*account).await.unwrap().unwrap();
+    spl_token::state::Account::unpack(&raw.data).unwrap().amount
+}
+
+/// Spins up a market plus a maker resting `base_qty` of base on the ask side
+/// at `price`, returning the market keys and the maker's owner keypair.
+async fn market_with_resting_ask(
+    ctx: &mut ProgramTestContext,
+    base_mint: &common::utils::MintInfo,
+    quote_mint: &common::utils::MintInfo,
+    mint_authority: &Keypair,
+    maker: &Keypair,
+    price: u64,
+    base_qty: u64,
+) -> MarketAccounts {
+    let market_admin = Keypair::new();
+    let market = create_market_and_accounts(
+        ctx,
+        dex_v4::ID,
+        base_mint,
+        quote_mint,
+        &market_admin.pubkey(),
+        None,
+        EVENT_CAPACITY,
+        1_000_000,
+        1,
+    )
+    .await;
+
+    let maker_base = create_token_account(ctx, &market.base_mint, &maker.pubkey()).await;
+    mint_to(ctx, &market.base_mint, &maker_base, mint_authority, base_qty)
+        .await
+        .unwrap();
+    let maker_account = initialize_user_account(ctx, &market.market, maker).await;
+    new_order(
+        ctx,
+        &market.market,
+        &market.event_queue,
+        &market.bids,
+        &market.asks,
+        &market.base_vault,
+        &market.quote_vault,
+        &maker_account,
+        &maker_base,
+        maker,
+        None,
+        Side::Ask,
+        price,
+        base_qty,
+        u64::MAX,
+    )
+    .await
+    .unwrap();
+    market
+}
+
+/// `send_take` fills against the book and settles straight into the taker's
+/// token accounts, posting nothing back to the book (IOC).
+#[tokio::test]
+async fn test_send_take_fills_without_posting() {
+    let mut program_test =
+        ProgramTest::new("dex_v4", dex_v4::ID, processor!(dex_v4::entrypoint::process));
+    let mint_authority = Keypair::new();
+    let base_mint = common::utils::mint_bootstrap(None, 6, &mut program_test, &mint_authority.pubkey());
+    let quote_mint =
+        common::utils::mint_bootstrap(None, 6, &mut program_test, &mint_authority.pubkey());
+    let mut ctx = program_test.start_with_context().await;
+
+    let maker = Keypair::new();
+    let market = market_with_resting_ask(
+        &mut ctx,
+        &base_mint,
+        &quote_mint,
+        &mint_authority,
+        &maker,
+        1000,
+        1000,
+    )
+    .await;
+
+    let taker = Keypair::new();
+    let taker_base = create_token_account(&mut ctx, &market.base_mint, &taker.pubkey()).await;
+    let taker_quote = create_token_account(&mut ctx, &market.quote_mint, &taker.pubkey()).await;
+    mint_to(&mut ctx, &market.quote_mint, &taker_quote, &mint_authority, 10_000_000)
+        .await
+        .unwrap();
+
+    send_take(
+        &mut ctx,
+        &market.market,
+        &market.event_queue,
+        &market.bids,
+        &market.asks,
+        &market.base_vault,
+        &market.quote_vault,
+        &market.market_signer,
+        &taker_base,
+        &taker_quote,
+        &taker,
+        Side::Bid,
+        1000,
+        10_000_000,
+        1000,
+    )
+    .await
+    .unwrap();
+
+    // Proceeds landed directly in the taker's base account ...
+    assert_eq!(token_balance(&mut ctx, &taker_base).await, 1000);
+    // ... and no taker leg was posted to the book (the bid side stays empty).
+    let mut bids_data = ctx.banks_client.get_account(market.bids).await.unwrap().unwrap().data;
+    let bid_slab = agnostic_orderbook::state::Slab::from_buffer(
+        &mut bids_data,
+        agnostic_orderbook::state::AccountTag::Bids,
+    );
+    assert!(bid_slab.find_max().is_none(), "IOC taker must not rest on the book");
+}
+
+/// `send_take` rejects when the matched base falls short of `min_base_qty`.
+#[tokio::test]
+async fn test_send_take_min_base_floor() {
+    let mut program_test =
+        ProgramTest::new("dex_v4", dex_v4::ID, processor!(dex_v4::entrypoint::process));
+    let mint_authority = Keypair::new();
+    let base_mint = common::utils::mint_bootstrap(None, 6, &mut program_test, &mint_authority.pubkey());
+    let quote_mint =
+        common::utils::mint_bootstrap(None, 6, &mut program_test, &mint_authority.pubkey());
+    let mut ctx = program_test.start_with_context().await;
+
+    let maker = Keypair::new();
+    let market = market_with_resting_ask(
+        &mut ctx,
+        &base_mint,
+        &quote_mint,
+        &mint_authority,
+        &maker,
+        1000,
+        500,
+    )
+    .await;
+
+    let taker = Keypair::new();
+    let taker_base = create_token_account(&mut ctx, &market.base_mint, &taker.pubkey()).await;
+    let taker_quote = create_token_account(&mut ctx, &market.quote_mint, &taker.pubkey()).await;
+    mint_to(&mut ctx, &market.quote_mint, &taker_quote, &mint_authority, 10_000_000)
+        .await
+        .unwrap();
+
+    // Only 500 base rests but the taker demands at least 1000 -> rejected.
+    let res = send_take(
+        &mut ctx,
+        &market.market,
+        &market.event_queue,
+        &market.bids,
+        &market.asks,
+        &market.base_vault,
+        &market.quote_vault,
+        &market.market_signer,
+        &taker_base,
+        &taker_quote,
+        &taker,
+        Side::Bid,
+        1000,
+        10_000_000,
+        1000,
+    )
+    .await;
+    assert!(res.is_err(), "filled base below min_base_qty must be rejected");
+}
+
+/// Resting orders owned by the taker are skipped rather than self-traded.
+#[tokio::test]
+async fn test_send_take_skips_self_trade() {
+    let mut program_test =
+        ProgramTest::new("dex_v4", dex_v4::ID, processor!(dex_v4::entrypoint::process));
+    let mint_authority = Keypair::new();
+    let base_mint = common::utils::mint_bootstrap(None, 6, &mut program_test, &mint_authority.pubkey());
+    let quote_mint =
+        common::utils::mint_bootstrap(None, 6, &mut program_test, &mint_authority.pubkey());
+    let mut ctx = program_test.start_with_context().await;
+
+    // The taker is also the maker of the only resting ask.
+    let taker = Keypair::new();
+    let market = market_with_resting_ask(
+        &mut ctx,
+        &base_mint,
+        &quote_mint,
+        &mint_authority,
+        &taker,
+        1000,
+        1000,
+    )
+    .await;
+
+    let taker_base = create_token_account(&mut ctx, &market.base_mint, &taker.pubkey()).await;
+    let taker_quote = create_token_account(&mut ctx, &market.quote_mint, &taker.pubkey()).await;
+    mint_to(&mut ctx, &market.quote_mint, &taker_quote, &mint_authority, 10_000_000)
+        .await
+        .unwrap();
+
+    // min_base_qty of 0 so the instruction succeeds having matched nothing.
+    send_take(
+        &mut ctx,
+        &market.market,
+        &market.event_queue,
+        &market.bids,
+        &market.asks,
+        &market.base_vault,
+        &market.quote_vault,
+        &market.market_signer,
+        &taker_base,
+        &taker_quote,
+        &taker,
+        Side::Bid,
+        1000,
+        10_000_000,
+        0,
+    )
+    .await
+    .unwrap();
+
+    // The self-owned ask was skipped: the taker received no base.
+    assert_eq!(token_balance(&mut ctx, &taker_base).await, 0);
+    // Cranking the event queue afterwards succeeds.
+    consume_events_crank(&mut ctx, &market.market, &market.event_queue, &taker.pubkey(), 4)
+        .await
+        .unwrap();
+}